@@ -0,0 +1,116 @@
+//! Downloading a watched run's logs and published artifacts, mirroring
+//! the CI driver's per-job artifact directory approach: once a run is
+//! terminal, pull everything it produced into a local directory.
+
+use crate::{AzpClient, AzpError, Config, RunId};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct LogCollection {
+    logs: Vec<LogEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogEntry {
+    id: u32,
+    #[serde(rename = "signedContent")]
+    signed_content: Option<SignedContent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedContent {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactCollection {
+    value: Vec<ArtifactEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactEntry {
+    name: String,
+    resource: ArtifactResource,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactResource {
+    #[serde(rename = "downloadUrl")]
+    download_url: String,
+}
+
+fn logs_url(config: &Config, run_id: RunId) -> String {
+    format!(
+        "https://dev.azure.com/{}/{}/_apis/pipelines/{}/runs/{}/logs?$expand=signedContent&api-version=7.1-preview.1",
+        config.organization, config.project, config.pipeline_id, run_id
+    )
+}
+
+fn artifacts_url(config: &Config, run_id: RunId) -> String {
+    format!(
+        "https://dev.azure.com/{}/{}/_apis/pipelines/{}/runs/{}/artifacts?api-version=7.1-preview.1",
+        config.organization, config.project, config.pipeline_id, run_id
+    )
+}
+
+impl AzpClient {
+    /// Enumerate a run's logs and download each one's signed content to
+    /// `dir/<log id>.log`. Each log is read fully into memory before it is
+    /// written. `dir` is created if it doesn't already exist.
+    pub async fn download_logs(
+        &self,
+        config: &Config,
+        run_id: RunId,
+        dir: &Path,
+    ) -> Result<(), AzpError> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        let collection: LogCollection = self.get(&logs_url(config, run_id)).await?.json().await?;
+
+        for log in collection.logs {
+            let Some(signed_content) = log.signed_content else {
+                continue;
+            };
+
+            let bytes = self
+                .http
+                .get(&signed_content.url)
+                .send()
+                .await?
+                .bytes()
+                .await?;
+
+            let path = dir.join(format!("{}.log", log.id));
+            tokio::fs::write(path, bytes).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Enumerate a run's published artifacts and download each one's zip
+    /// to `dir/<artifact name>.zip`. `dir` is created if it doesn't
+    /// already exist.
+    pub async fn download_artifacts(
+        &self,
+        config: &Config,
+        run_id: RunId,
+        dir: &Path,
+    ) -> Result<(), AzpError> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        let collection: ArtifactCollection = self
+            .get(&artifacts_url(config, run_id))
+            .await?
+            .json()
+            .await?;
+
+        for artifact in collection.value {
+            let bytes = self.get(&artifact.resource.download_url).await?.bytes().await?;
+            let path = dir.join(format!("{}.zip", artifact.name));
+            tokio::fs::write(path, bytes).await?;
+        }
+
+        Ok(())
+    }
+}
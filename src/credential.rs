@@ -0,0 +1,129 @@
+//! Credential abstraction used by [`crate::AzpClient`] to build the
+//! `Authorization` header for every request, following the Azure SDK's
+//! `Arc<dyn TokenCredential>` design.
+
+use crate::AzpError;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// Azure DevOps resource ID, used as the OAuth2 scope when requesting a
+// bearer token via the client-credentials flow.
+const AZURE_DEVOPS_RESOURCE_SCOPE: &str = "499b84ac-1321-427f-aa17-267ca6975798/.default";
+// Refresh the cached token a minute before it actually expires so a
+// request never gets sent with a token that dies mid-flight.
+const EXPIRY_SAFETY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Something that can produce an `Authorization` header value on demand.
+#[async_trait]
+pub trait TokenCredential: Send + Sync {
+    async fn authorization_header(&self) -> Result<String, AzpError>;
+}
+
+/// The original auth mode: a long-lived Personal Access Token sent as
+/// HTTP Basic auth.
+pub struct PatCredential {
+    pat: String,
+}
+
+impl PatCredential {
+    pub fn new(pat: impl Into<String>) -> Self {
+        PatCredential { pat: pat.into() }
+    }
+}
+
+#[async_trait]
+impl TokenCredential for PatCredential {
+    async fn authorization_header(&self) -> Result<String, AzpError> {
+        Ok(format!("Basic {}", base64::encode(format!(":{}", self.pat))))
+    }
+}
+
+struct CachedToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+/// Azure AD OAuth2 client-credentials flow: exchanges a service
+/// principal's client id/secret/tenant for a bearer token and caches it
+/// until shortly before it expires, so CI can run under a managed
+/// service principal instead of a long-lived PAT.
+pub struct ClientSecretCredential {
+    tenant_id: String,
+    client_id: String,
+    client_secret: String,
+    http: reqwest::Client,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+impl ClientSecretCredential {
+    pub fn new(
+        tenant_id: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        ClientSecretCredential {
+            tenant_id: tenant_id.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            http: reqwest::Client::new(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    async fn fetch_token(&self) -> Result<CachedToken, AzpError> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+            expires_in: u64,
+        }
+
+        let url = format!(
+            "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+            self.tenant_id
+        );
+        let response = self
+            .http
+            .post(&url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("scope", AZURE_DEVOPS_RESOURCE_SCOPE),
+            ])
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(AzpError::Api { status, message });
+        }
+
+        let parsed: TokenResponse = response.json().await?;
+        Ok(CachedToken {
+            access_token: parsed.access_token,
+            expires_at: Instant::now()
+                + Duration::from_secs(parsed.expires_in).saturating_sub(EXPIRY_SAFETY_MARGIN),
+        })
+    }
+}
+
+#[async_trait]
+impl TokenCredential for ClientSecretCredential {
+    async fn authorization_header(&self) -> Result<String, AzpError> {
+        let mut cached = self.cached.lock().await;
+
+        let needs_refresh = match cached.as_ref() {
+            Some(token) => Instant::now() >= token.expires_at,
+            None => true,
+        };
+
+        if needs_refresh {
+            *cached = Some(self.fetch_token().await?);
+        }
+
+        Ok(format!("Bearer {}", cached.as_ref().unwrap().access_token))
+    }
+}
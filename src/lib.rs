@@ -0,0 +1,668 @@
+//! Library API for triggering and watching Azure Pipelines runs.
+//!
+//! The CLI binary (`main.rs`) is a thin wrapper over this crate: parse
+//! arguments into a [`Config`], build an [`AzpClient`], then call
+//! [`AzpClient::run_pipeline`] and optionally [`AzpClient::watch_run`].
+//! Other Rust programs can depend on this crate directly to embed
+//! pipeline triggering without shelling out to the binary.
+
+mod artifacts;
+mod credential;
+mod notifier;
+mod plan;
+
+pub use credential::{ClientSecretCredential, PatCredential, TokenCredential};
+pub use notifier::{Notifier, RunEvent, SlackNotifier, WebhookNotifier};
+pub use plan::{run_plan, NodeOutcome, Plan, PlanNode};
+
+use rand::Rng;
+use reqwest::{header, Client, Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Config {
+    pub organization: String,
+    pub project: String,
+    pub pipeline_id: u32,
+    pub template_parameters: String,
+    pub watch: bool,
+}
+
+/// Azure DevOps pipeline run identifier.
+pub type RunId = u32;
+
+/// Terminal (or non-terminal) state of a pipeline run, parsed from the
+/// Azure DevOps `state` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    InProgress,
+    Completed,
+    Canceled,
+    Failed,
+}
+
+impl RunState {
+    /// Azure DevOps only ever reports `unknown`/`inProgress`/`canceling`/
+    /// `completed` in `state`; whether a completed run succeeded, failed,
+    /// or was canceled lives in the separate `result` field
+    /// (`succeeded`/`failed`/`canceled`), so a run is only terminal once
+    /// `state == "completed"`, and `result` decides which terminal state.
+    fn from_api(state: &str, result: Option<&str>) -> RunState {
+        if state != "completed" {
+            return RunState::InProgress;
+        }
+
+        match result {
+            Some("failed") => RunState::Failed,
+            Some("canceled") => RunState::Canceled,
+            _ => RunState::Completed,
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, RunState::InProgress)
+    }
+}
+
+impl fmt::Display for RunState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RunState::InProgress => "inProgress",
+            RunState::Completed => "completed",
+            RunState::Canceled => "canceled",
+            RunState::Failed => "failed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Errors surfaced by [`AzpClient`]. Replaces the old mix of
+/// `Box<dyn Error>`, `panic!`, and `process::exit` with a real error type.
+#[derive(Debug)]
+pub enum AzpError {
+    /// The HTTP transport failed or a response body could not be read or
+    /// decoded. Connection errors and timeouts are first retried per the
+    /// retry policy; other transport errors are returned at once.
+    Http(reqwest::Error),
+    /// The API responded with a non-success status.
+    Api {
+        status: StatusCode,
+        message: String,
+    },
+    /// `template_parameters` was not valid JSON, or was valid JSON that
+    /// wasn't a JSON object.
+    InvalidTemplateParameters(String),
+    /// A required credential (e.g. a PAT) was not provided.
+    MissingCredential(String),
+    /// Writing a downloaded log or artifact to disk failed.
+    Io(std::io::Error),
+    /// A `--plan` was malformed (an unknown `depends_on` id or a cycle),
+    /// or one of its node tasks panicked while the plan ran.
+    InvalidPlan(String),
+}
+
+impl fmt::Display for AzpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AzpError::Http(err) => write!(f, "HTTP request failed: {}", err),
+            AzpError::Api { status, message } => {
+                write!(f, "Azure DevOps API error, status {}: {}", status, message)
+            }
+            AzpError::InvalidTemplateParameters(msg) => {
+                write!(f, "invalid template parameters: {}", msg)
+            }
+            AzpError::MissingCredential(msg) => write!(f, "{}", msg),
+            AzpError::Io(err) => write!(f, "I/O error: {}", err),
+            AzpError::InvalidPlan(msg) => write!(f, "invalid plan: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AzpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AzpError::Http(err) => Some(err),
+            AzpError::Io(err) => Some(err),
+            AzpError::Api { .. }
+            | AzpError::InvalidTemplateParameters(_)
+            | AzpError::MissingCredential(_)
+            | AzpError::InvalidPlan(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for AzpError {
+    fn from(err: reqwest::Error) -> Self {
+        AzpError::Http(err)
+    }
+}
+
+impl From<std::io::Error> for AzpError {
+    fn from(err: std::io::Error) -> Self {
+        AzpError::Io(err)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PipeLineResponse {
+    pipeline: PipelineInfo,
+    id: u32,
+    state: String,
+    #[serde(default)]
+    result: Option<String>,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PipelineListResponse {
+    value: Vec<PipelineSummary>,
+}
+
+/// A pipeline as returned by `AzpClient::list_pipelines`.
+#[derive(Debug, Deserialize)]
+pub struct PipelineSummary {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub folder: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PipelineInfo {
+    //url: String,
+    //id: i32,
+    //revision: i32,
+    name: String,
+    //folder: String,
+}
+
+// --------------------------------------------------
+// RETRY POLICY
+// --------------------------------------------------
+
+/// Exponential backoff with full jitter, shared by every request an
+/// `AzpClient` makes. After a retryable failure of attempt `n` (counting
+/// from 0) the delay before the next attempt is a uniformly random
+/// duration in `[0, min(cap, base * 2^n)]`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub base: Duration,
+    pub cap: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            base: Duration::from_secs(1),
+            cap: Duration::from_secs(60),
+            max_attempts: 5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(base: Duration, cap: Duration, max_attempts: u32) -> Self {
+        RetryPolicy {
+            base,
+            cap,
+            max_attempts,
+        }
+    }
+
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let exp = self.base.as_secs_f64() * 2f64.powi(attempt as i32);
+        let capped = exp.min(self.cap.as_secs_f64());
+        let jittered = rand::thread_rng().gen_range(0.0..=capped);
+        Duration::from_secs_f64(jittered)
+    }
+
+    fn is_retryable_status(status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    }
+
+    fn is_retryable_error(err: &reqwest::Error) -> bool {
+        err.is_timeout() || err.is_connect()
+    }
+}
+
+// --------------------------------------------------
+// AZP CLIENT
+// --------------------------------------------------
+
+/// Thin wrapper around `reqwest::Client` that centralizes auth header
+/// construction and retry/backoff, the way the Service Fabric SDK's
+/// client wraps its own HTTP transport. Built through a `ClientBuilder`.
+pub struct AzpClient {
+    pub(crate) http: Client,
+    credential: Arc<dyn TokenCredential>,
+    retry: RetryPolicy,
+    notifiers: Vec<Arc<dyn Notifier>>,
+}
+
+impl AzpClient {
+    async fn send_with_retry<F>(&self, mut build_request: F) -> Result<Response, AzpError>
+    where
+        F: FnMut() -> RequestBuilder,
+    {
+        let mut attempt: u32 = 0;
+        loop {
+            match build_request().send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    if status.is_success() || !RetryPolicy::is_retryable_status(status) {
+                        return Ok(response);
+                    }
+                    if attempt + 1 >= self.retry.max_attempts {
+                        return Ok(response);
+                    }
+                }
+                Err(err) => {
+                    if !RetryPolicy::is_retryable_error(&err)
+                        || attempt + 1 >= self.retry.max_attempts
+                    {
+                        return Err(AzpError::Http(err));
+                    }
+                }
+            }
+
+            tokio::time::sleep(self.retry.delay_for(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    pub(crate) async fn get(&self, url: &str) -> Result<Response, AzpError> {
+        let auth = self.credential.authorization_header().await?;
+        self.send_with_retry(|| {
+            self.http
+                .request(Method::GET, url)
+                .header(header::ACCEPT, "application/json")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, &auth)
+        })
+        .await
+    }
+
+    async fn post(&self, url: &str, body: &Value) -> Result<Response, AzpError> {
+        let auth = self.credential.authorization_header().await?;
+        self.send_with_retry(|| {
+            self.http
+                .request(Method::POST, url)
+                .header(header::ACCEPT, "application/json")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, &auth)
+                .json(body)
+        })
+        .await
+    }
+
+    async fn patch(&self, url: &str, body: &Value) -> Result<Response, AzpError> {
+        let auth = self.credential.authorization_header().await?;
+        self.send_with_retry(|| {
+            self.http
+                .request(Method::PATCH, url)
+                .header(header::ACCEPT, "application/json")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, &auth)
+                .json(body)
+        })
+        .await
+    }
+
+    /// Trigger a pipeline run and return its `RunId`.
+    pub async fn run_pipeline(&self, config: &Config) -> Result<RunId, AzpError> {
+        let body = param_str_to_json(&config.template_parameters)?;
+        let response = self.post(&pipeline_run_url(config), &body).await?;
+        let status = response.status();
+
+        if status != StatusCode::OK {
+            let message = response
+                .json::<ApiResponse>()
+                .await
+                .map(|api| api.message)
+                .unwrap_or_else(|_| "no message returned by the API".to_string());
+            return Err(AzpError::Api { status, message });
+        }
+
+        let parsed: PipeLineResponse = response.json().await?;
+        Ok(parsed.id)
+    }
+
+    /// Poll a run until it reaches a terminal state, returning that state.
+    /// The returned state (and the `state` sent to notifiers) distinguishes
+    /// `Failed`/`Canceled`/`Completed` correctly, so callers can exit
+    /// non-zero on `RunState::Failed` without re-deriving the outcome
+    /// themselves.
+    pub async fn watch_run(&self, config: &Config, run_id: RunId) -> Result<RunState, AzpError> {
+        let url = pipeline_status_url(config, run_id);
+
+        loop {
+            let response = self.get(&url).await?;
+            let status = response.status();
+
+            if !status.is_success() {
+                let message = response
+                    .json::<ApiResponse>()
+                    .await
+                    .map(|api| api.message)
+                    .unwrap_or_else(|_| "no message returned by the API".to_string());
+                return Err(AzpError::Api { status, message });
+            }
+
+            let parsed: PipeLineResponse = response.json().await?;
+            let state = RunState::from_api(&parsed.state, parsed.result.as_deref());
+
+            if state.is_terminal() {
+                let event = RunEvent {
+                    pipeline: parsed.pipeline.name.clone(),
+                    run_id: parsed.id,
+                    state,
+                    url: parsed.url.clone(),
+                };
+                self.dispatch_notifications(&event).await;
+                return Ok(state);
+            }
+
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    }
+
+    /// Query a run's current state once, without looping.
+    pub async fn run_status(&self, config: &Config, run_id: RunId) -> Result<RunState, AzpError> {
+        let response = self.get(&pipeline_status_url(config, run_id)).await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let message = response
+                .json::<ApiResponse>()
+                .await
+                .map(|api| api.message)
+                .unwrap_or_else(|_| "no message returned by the API".to_string());
+            return Err(AzpError::Api { status, message });
+        }
+
+        let parsed: PipeLineResponse = response.json().await?;
+        Ok(RunState::from_api(&parsed.state, parsed.result.as_deref()))
+    }
+
+    /// Request that a run be canceled, returning the state the API
+    /// reports immediately after the request (cancellation itself is
+    /// asynchronous; poll `run_status` or `watch_run` to see it land).
+    pub async fn cancel_run(&self, config: &Config, run_id: RunId) -> Result<RunState, AzpError> {
+        let body = json!({ "state": "canceling" });
+        let response = self.patch(&pipeline_status_url(config, run_id), &body).await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let message = response
+                .json::<ApiResponse>()
+                .await
+                .map(|api| api.message)
+                .unwrap_or_else(|_| "no message returned by the API".to_string());
+            return Err(AzpError::Api { status, message });
+        }
+
+        let parsed: PipeLineResponse = response.json().await?;
+        Ok(RunState::from_api(&parsed.state, parsed.result.as_deref()))
+    }
+
+    /// List the pipelines defined in a project.
+    pub async fn list_pipelines(&self, config: &Config) -> Result<Vec<PipelineSummary>, AzpError> {
+        let response = self.get(&pipelines_list_url(config)).await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let message = response
+                .json::<ApiResponse>()
+                .await
+                .map(|api| api.message)
+                .unwrap_or_else(|_| "no message returned by the API".to_string());
+            return Err(AzpError::Api { status, message });
+        }
+
+        let parsed: PipelineListResponse = response.json().await?;
+        Ok(parsed.value)
+    }
+
+    async fn dispatch_notifications(&self, event: &RunEvent) {
+        for notifier in &self.notifiers {
+            if let Err(err) = notifier.notify(event).await {
+                eprintln!("Warning: notification failed: {}", err);
+            }
+        }
+    }
+}
+
+/// Builder for `AzpClient`, in the style of the Service Fabric SDK's
+/// builder, with `.retry()` / `.timeout()` plus the credential and
+/// notifier setters. There is no `.endpoint()`: every request goes to
+/// `https://dev.azure.com`.
+pub struct ClientBuilder {
+    credential: Option<Arc<dyn TokenCredential>>,
+    timeout: Duration,
+    retry: RetryPolicy,
+    notifiers: Vec<Arc<dyn Notifier>>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        ClientBuilder {
+            credential: None,
+            timeout: Duration::from_secs(10),
+            retry: RetryPolicy::default(),
+            notifiers: Vec::new(),
+        }
+    }
+
+    /// Register a sink to be notified when a watched run reaches a
+    /// terminal state. Can be called more than once to fan out to
+    /// several sinks.
+    pub fn notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifiers.push(notifier);
+        self
+    }
+
+    /// Authenticate with a Personal Access Token sent as HTTP Basic auth.
+    pub fn pat(mut self, pat: &str) -> Self {
+        self.credential = Some(Arc::new(PatCredential::new(pat)));
+        self
+    }
+
+    /// Authenticate with an arbitrary `TokenCredential`, e.g. a
+    /// `ClientSecretCredential` for running under a service principal.
+    pub fn credential(mut self, credential: Arc<dyn TokenCredential>) -> Self {
+        self.credential = Some(credential);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> Result<AzpClient, AzpError> {
+        let credential = self.credential.ok_or_else(|| {
+            AzpError::MissingCredential(
+                "AzpClient requires a credential (pat() or credential())".to_string(),
+            )
+        })?;
+        let http = Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(AzpError::Http)?;
+
+        Ok(AzpClient {
+            http,
+            credential,
+            retry: self.retry,
+            notifiers: self.notifiers,
+        })
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// --------------------------------------------------
+
+fn param_to_request_body(template_params: &str) -> Result<Value, AzpError> {
+    let json_obj: Value = serde_json::from_str(template_params)
+        .map_err(|err| AzpError::InvalidTemplateParameters(err.to_string()))?;
+
+    // `templateParameters` must be a JSON object; reject anything else
+    // (an array, string, number, bool, or null) instead of silently
+    // coercing it to an empty one.
+    let template_parameters = match json_obj {
+        Value::Object(map) => map,
+        other => {
+            return Err(AzpError::InvalidTemplateParameters(format!(
+                "expected a JSON object, got {}",
+                other
+            )))
+        }
+    };
+
+    Ok(json!({
+        "resources": {
+            "repositories": {
+                "self": {},
+            },
+        },
+        "templateParameters": template_parameters,
+    }))
+}
+
+// create a valid json body from the template parameters
+fn param_str_to_json(template_parameters: &str) -> Result<Value, AzpError> {
+    if template_parameters.is_empty() {
+        Ok(Value::Object(Map::new()))
+    } else {
+        param_to_request_body(template_parameters)
+    }
+}
+
+// --------------------------------------------------
+
+// Pipeline run URL builder function
+fn pipeline_run_url(config: &Config) -> String {
+    format!(
+        "https://dev.azure.com/{}/{}/_apis/pipelines/{}/runs?api-version=7.1-preview.1",
+        config.organization, config.project, config.pipeline_id
+    )
+}
+
+fn pipelines_list_url(config: &Config) -> String {
+    format!(
+        "https://dev.azure.com/{}/{}/_apis/pipelines?api-version=7.1-preview.1",
+        config.organization, config.project
+    )
+}
+
+fn pipeline_status_url(config: &Config, run_id: RunId) -> String {
+    format!(
+        "https://dev.azure.com/{}/{}/_apis/pipelines/{}/runs/{}?api-version=7.1-preview.1",
+        config.organization, config.project, config.pipeline_id, run_id
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_state_from_api_derives_terminal_state_from_result() {
+        assert_eq!(
+            RunState::from_api("completed", Some("succeeded")),
+            RunState::Completed
+        );
+        assert_eq!(
+            RunState::from_api("completed", Some("failed")),
+            RunState::Failed
+        );
+        assert_eq!(
+            RunState::from_api("completed", Some("canceled")),
+            RunState::Canceled
+        );
+    }
+
+    #[test]
+    fn run_state_from_api_is_in_progress_until_state_is_completed() {
+        assert_eq!(RunState::from_api("inProgress", None), RunState::InProgress);
+        assert_eq!(RunState::from_api("canceling", None), RunState::InProgress);
+        assert_eq!(RunState::from_api("unknown", None), RunState::InProgress);
+    }
+
+    #[test]
+    fn delay_for_stays_within_the_jitter_bound() {
+        let policy = RetryPolicy::new(Duration::from_secs(1), Duration::from_secs(60), 5);
+        for attempt in 0..8 {
+            let bound = (1f64 * 2f64.powi(attempt as i32)).min(60.0);
+            let delay = policy.delay_for(attempt).as_secs_f64();
+            assert!(delay >= 0.0 && delay <= bound, "attempt {}: delay {}", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn delay_for_is_capped_for_large_attempts() {
+        let policy = RetryPolicy::new(Duration::from_secs(1), Duration::from_secs(60), 5);
+        let delay = policy.delay_for(10).as_secs_f64();
+        assert!(delay <= 60.0, "delay {} exceeded cap", delay);
+    }
+
+    #[test]
+    fn retryable_statuses_are_429_and_5xx() {
+        assert!(RetryPolicy::is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(RetryPolicy::is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(RetryPolicy::is_retryable_status(StatusCode::BAD_GATEWAY));
+    }
+
+    #[test]
+    fn non_retryable_statuses_fail_immediately() {
+        assert!(!RetryPolicy::is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!RetryPolicy::is_retryable_status(StatusCode::UNAUTHORIZED));
+        assert!(!RetryPolicy::is_retryable_status(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn param_str_to_json_defaults_empty_input_to_empty_object() {
+        let value = param_str_to_json("").unwrap();
+        assert_eq!(value, Value::Object(Map::new()));
+    }
+
+    #[test]
+    fn param_str_to_json_accepts_a_json_object() {
+        let value = param_str_to_json(r#"{"env":"prod"}"#).unwrap();
+        let template_parameters = value.get("templateParameters").unwrap();
+        assert_eq!(template_parameters, &json!({"env": "prod"}));
+    }
+
+    #[test]
+    fn param_str_to_json_rejects_non_object_json() {
+        assert!(param_str_to_json("[1,2]").is_err());
+        assert!(param_str_to_json("\"just a string\"").is_err());
+        assert!(param_str_to_json("42").is_err());
+        assert!(param_str_to_json("null").is_err());
+    }
+
+    #[test]
+    fn param_str_to_json_rejects_invalid_json() {
+        assert!(param_str_to_json("{not json").is_err());
+    }
+}
@@ -1,80 +1,264 @@
-use clap::{App, Arg};
-use reqwest::{header, Client, ClientBuilder};
-use serde::Deserialize;
-use serde_json::{json, Map, Value};
+use azp_runs::{
+    run_plan, AzpClient, ClientBuilder, ClientSecretCredential, Config, NodeOutcome, PatCredential,
+    Plan, RunState, SlackNotifier, TokenCredential, WebhookNotifier,
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use std::env;
 use std::error::Error;
-use std::thread;
-use std::time::Duration;
+use std::path::Path;
+use std::sync::Arc;
 
 // Azure DevOps Personal Access Token (PAT)
 const AZURE_DEVOPS_PAT_ENV: &str = "AZURE_DEVOPS_EXT_PAT";
 
-#[derive(Debug)]
-pub struct Config {
-    pub organization: String,
-    pub project: String,
-    pub pipeline_id: u32,
-    pub template_parameters: String,
-    pub watch: bool,
+// Azure AD service principal, used for the "service-principal" auth mode
+const AZURE_TENANT_ID_ENV: &str = "AZURE_TENANT_ID";
+const AZURE_CLIENT_ID_ENV: &str = "AZURE_CLIENT_ID";
+const AZURE_CLIENT_SECRET_ENV: &str = "AZURE_CLIENT_SECRET";
+
+// clap 2.x only populates a `.global(true)` arg's value on the
+// `ArgMatches` it was actually parsed against: given after the
+// subcommand name (e.g. `azp list --organization X`), it lands in the
+// subcommand's matches, not the top-level ones. Fall back to whichever
+// matches actually captured it.
+fn global_value_of<'a>(matches: &'a ArgMatches<'a>, name: &str) -> Option<&'a str> {
+    matches
+        .subcommand()
+        .1
+        .and_then(|sub_m| sub_m.value_of(name))
+        .or_else(|| matches.value_of(name))
 }
 
-#[derive(Debug, Deserialize)]
-struct PipeLineResponse {
-    pipeline: PipelineInfo,
-    id: u32,
-    state: String,
-}
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let matches = build_app().get_matches();
+
+    let organization = match global_value_of(&matches, "organization") {
+        Some(organization) => organization.to_string(),
+        None => {
+            eprintln!("Error: --organization is required");
+            std::process::exit(1);
+        }
+    };
+    let project = match global_value_of(&matches, "project") {
+        Some(project) => project.to_string(),
+        None => {
+            eprintln!("Error: --project is required");
+            std::process::exit(1);
+        }
+    };
 
-#[derive(Debug, Deserialize)]
-struct ApiResponse {
-    message: String,
+    let credential = match build_credential(global_value_of(&matches, "auth")) {
+        Ok(credential) => credential,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut builder = ClientBuilder::new().credential(credential);
+    if let Some(url) = matches.value_of("notify_webhook") {
+        builder = builder.notifier(Arc::new(WebhookNotifier::new(url.to_string())));
+    }
+    if let Some(url) = matches.value_of("notify_slack") {
+        builder = builder.notifier(Arc::new(SlackNotifier::new(url.to_string())));
+    }
+    let client = Arc::new(builder.build()?);
+
+    match matches.subcommand() {
+        ("list", Some(_)) => run_list(&client, &organization, &project).await,
+        ("status", Some(sub_m)) => run_status(&client, &organization, &project, sub_m).await,
+        ("cancel", Some(sub_m)) => run_cancel(&client, &organization, &project, sub_m).await,
+        _ => run_trigger(client, organization, project, &matches).await,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct PipelineInfo {
-    //url: String,
-    id: i32,
-    //revision: i32,
-    name: String,
-    //folder: String,
+fn config_for(organization: &str, project: &str, pipeline_id: u32) -> Config {
+    Config {
+        organization: organization.to_string(),
+        project: project.to_string(),
+        pipeline_id,
+        template_parameters: String::new(),
+        watch: false,
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let config = get_args().unwrap();
+async fn run_list(
+    client: &AzpClient,
+    organization: &str,
+    project: &str,
+) -> Result<(), Box<dyn Error>> {
+    let config = config_for(organization, project, 0);
 
-    // get PAT token from ENV variable
-    let pat = match get_pat_from_env() {
-        Ok(pat) => pat,
+    match client.list_pipelines(&config).await {
+        Ok(pipelines) => {
+            for pipeline in pipelines {
+                println!("{}\t{}\t{}", pipeline.id, pipeline.name, pipeline.folder);
+            }
+            Ok(())
+        }
         Err(err) => {
             eprintln!("Error: {}", err);
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    // Create an HTTP client
-    //let client = Client::new();
-    let client = ClientBuilder::new()
-        .timeout(Duration::from_secs(10))
-        .build()?;
+async fn run_status(
+    client: &AzpClient,
+    organization: &str,
+    project: &str,
+    sub_m: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let pipeline_id: u32 = sub_m.value_of("pipeline_id").unwrap().parse()?;
+    let run_id: u32 = sub_m.value_of("run_id").unwrap().parse()?;
+    let config = config_for(organization, project, pipeline_id);
+
+    match client.run_status(&config, run_id).await {
+        Ok(state) => {
+            println!("{}", state);
+            Ok(())
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let response = match pipeline_exec(&client, &config, &pat).await {
-        Ok(response) => response,
+async fn run_cancel(
+    client: &AzpClient,
+    organization: &str,
+    project: &str,
+    sub_m: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let pipeline_id: u32 = sub_m.value_of("pipeline_id").unwrap().parse()?;
+    let run_id: u32 = sub_m.value_of("run_id").unwrap().parse()?;
+    let config = config_for(organization, project, pipeline_id);
+
+    match client.cancel_run(&config, run_id).await {
+        Ok(state) => {
+            println!("Run [{}] is now: {}", run_id, state);
+            Ok(())
+        }
         Err(err) => {
             eprintln!("Error: {}", err);
             std::process::exit(1);
         }
+    }
+}
+
+async fn run_trigger(
+    client: Arc<AzpClient>,
+    organization: String,
+    project: String,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(plan_path) = matches.value_of("plan") {
+        let config = config_for(&organization, &project, 0);
+        return run_dag(client, &config, plan_path).await;
+    }
+
+    let pipeline_id: u32 = match matches.value_of("pipeline_id") {
+        Some(pipeline_id) => pipeline_id.parse()?,
+        None => {
+            eprintln!("Error: --pipeline_id is required (or pass --plan)");
+            std::process::exit(1);
+        }
+    };
+    let config = Config {
+        organization,
+        project,
+        pipeline_id,
+        template_parameters: matches
+            .value_of("template_parameters")
+            .unwrap_or("")
+            .to_string(),
+        watch: matches.is_present("watch"),
     };
 
-    match pipeline_validate_response(&client, response, &config, &pat).await {
-        Ok(()) => (),
+    let run_id = match client.run_pipeline(&config).await {
+        Ok(run_id) => {
+            println!("Pipeline triggered successfully, run id = [{}]", run_id);
+            run_id
+        }
         Err(err) => {
             eprintln!("Error: {}", err);
             std::process::exit(1);
         }
     };
 
+    if config.watch {
+        match client.watch_run(&config, run_id).await {
+            Ok(state) => {
+                println!("Pipeline has finished with status: {}", state);
+
+                if let Some(dir) = matches.value_of("logs_dir") {
+                    if let Err(err) = client.download_logs(&config, run_id, Path::new(dir)).await {
+                        eprintln!("Error downloading logs: {}", err);
+                    }
+                }
+                if let Some(dir) = matches.value_of("artifacts_dir") {
+                    if let Err(err) = client
+                        .download_artifacts(&config, run_id, Path::new(dir))
+                        .await
+                    {
+                        eprintln!("Error downloading artifacts: {}", err);
+                    }
+                }
+
+                if state == RunState::Failed {
+                    std::process::exit(1);
+                }
+            }
+            Err(err) => {
+                eprintln!("Error in watch function: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+
+/// Load a `--plan` file and run its nodes as a dependency graph,
+/// printing every node's final outcome. Exits non-zero if any node
+/// failed, was canceled, errored, or was aborted.
+async fn run_dag(
+    client: Arc<AzpClient>,
+    config: &Config,
+    plan_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let plan_json = std::fs::read_to_string(plan_path)?;
+    let plan: Plan = serde_json::from_str(&plan_json)?;
+
+    let results = run_plan(client, config, plan).await?;
+
+    let mut had_failure = false;
+    for (id, outcome) in &results {
+        match outcome {
+            NodeOutcome::Finished(state) => {
+                println!("[{}] finished with status: {}", id, state);
+                if *state != RunState::Completed {
+                    had_failure = true;
+                }
+            }
+            NodeOutcome::Aborted => {
+                println!("[{}] aborted: a dependency did not complete successfully", id);
+                had_failure = true;
+            }
+            NodeOutcome::Error(msg) => {
+                println!("[{}] errored: {}", id, msg);
+                had_failure = true;
+            }
+        }
+    }
+
+    if had_failure {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
@@ -87,14 +271,34 @@ fn is_valid_u32(value: String) -> Result<(), String> {
     }
 }
 
-pub fn get_args() -> Result<Config, Box<dyn Error>> {
-    // Define and parse command-line arguments using clap
-    let matches = App::new("azure_pipelines_runs")
+fn pipeline_id_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("pipeline_id")
+        .short("i")
+        .long("pipeline_id")
+        .takes_value(true)
+        .help("Azure Pipeline ID")
+        .validator(is_valid_u32)
+}
+
+fn run_id_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("run_id")
+        .long("run-id")
+        .required(true)
+        .takes_value(true)
+        .help("Pipeline run ID")
+        .validator(is_valid_u32)
+}
+
+/// Build the CLI. `trigger` (the original single-pipeline behavior) is
+/// the default when no subcommand is given, alongside `list` / `status`
+/// / `cancel` pipeline-ops subcommands.
+fn build_app<'a, 'b>() -> App<'a, 'b> {
+    App::new("azure_pipelines_runs")
         .arg(
             Arg::with_name("organization")
                 .short("o")
                 .long("organization")
-                .required(true)
+                .global(true)
                 .takes_value(true)
                 .help("Azure DevOps Organization name"),
         )
@@ -102,19 +306,23 @@ pub fn get_args() -> Result<Config, Box<dyn Error>> {
             Arg::with_name("project")
                 .short("p")
                 .long("project")
-                .required(true)
+                .global(true)
                 .takes_value(true)
                 .help("Azure DevOps Project"),
         )
         .arg(
-            Arg::with_name("pipeline_id")
-                .short("i")
-                .long("pipeline_id")
-                .required(true)
+            Arg::with_name("auth")
+                .long("auth")
+                .global(true)
                 .takes_value(true)
-                .help("Azure Pipeline ID")
-                .validator(is_valid_u32),
+                .possible_values(&["pat", "service-principal"])
+                .help(
+                    "Authentication mode. Defaults to auto-detecting a service \
+                     principal from AZURE_TENANT_ID/AZURE_CLIENT_ID/AZURE_CLIENT_SECRET, \
+                     falling back to a PAT.",
+                ),
         )
+        .arg(pipeline_id_arg())
         .arg(
             Arg::with_name("template_parameters")
                 .short("t")
@@ -131,27 +339,55 @@ pub fn get_args() -> Result<Config, Box<dyn Error>> {
                 .takes_value(false)
                 .help("Watch pipeline status and block untill finished"),
         )
-        .get_matches();
-
-    Ok(Config {
-        organization: matches
-            .value_of("organization")
-            .expect("organization is required")
-            .to_string(),
-        project: matches
-            .value_of("project")
-            .expect("project is required")
-            .to_string(),
-        pipeline_id: matches
-            .value_of("pipeline_id")
-            .expect("pipeline_id is required")
-            .parse::<u32>()?,
-        template_parameters: matches
-            .value_of("template_parameters")
-            .unwrap_or("")
-            .to_string(),
-        watch: matches.is_present("watch"),
-    })
+        .arg(
+            Arg::with_name("notify_webhook")
+                .long("notify-webhook")
+                .takes_value(true)
+                .required(false)
+                .help("POST a completion payload to this URL when --watch reaches a terminal state"),
+        )
+        .arg(
+            Arg::with_name("notify_slack")
+                .long("notify-slack")
+                .takes_value(true)
+                .required(false)
+                .help("POST a Slack incoming-webhook message to this URL when --watch reaches a terminal state"),
+        )
+        .arg(
+            Arg::with_name("logs_dir")
+                .long("logs-dir")
+                .takes_value(true)
+                .required(false)
+                .help("Download the run's logs into this directory once --watch reaches a terminal state"),
+        )
+        .arg(
+            Arg::with_name("artifacts_dir")
+                .long("artifacts-dir")
+                .takes_value(true)
+                .required(false)
+                .help("Download the run's published artifacts into this directory once --watch reaches a terminal state"),
+        )
+        .arg(
+            Arg::with_name("plan")
+                .long("plan")
+                .takes_value(true)
+                .required(false)
+                .conflicts_with("pipeline_id")
+                .help("Run a DAG of pipelines described by this JSON plan file instead of a single pipeline"),
+        )
+        .subcommand(SubCommand::with_name("list").about("List the pipelines defined in a project"))
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Query a run's state without looping")
+                .arg(pipeline_id_arg().required(true))
+                .arg(run_id_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel")
+                .about("Cancel a run")
+                .arg(pipeline_id_arg().required(true))
+                .arg(run_id_arg()),
+        )
 }
 
 // --------------------------------------------------
@@ -172,178 +408,30 @@ fn get_pat_from_env() -> Result<String, Box<dyn Error>> {
 
 // --------------------------------------------------
 
-fn param_to_request_body(template_params: &str) -> Result<Value, Box<dyn Error>> {
-    // Parse the JSON string into a serde_json::Value
-    let parsed_json_result = serde_json::from_str(template_params);
-
-    match parsed_json_result {
-        Ok(json_obj) => {
-            // Ensure the JSON object is a JSON object (not an array, null, etc.)
-            if let Value::Object(template_parameters) = json_obj {
-                // Prepare the JSON request body with template parameters
-                let request_body = json!({
-                    "resources": {
-                        "repositories": {
-                            "self": {},
-                        },
-                    },
-                    "templateParameters": template_parameters,
-                });
-
-                // Returns the generated JSON for testing
-                Ok(request_body)
-            } else {
-                panic!("Invalid JSON object.");
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to parse JSON: {}", e);
-            Err(Box::new(e))
-        }
-    }
-}
-
-// create a valid json body from the template parameters
-fn param_str_to_json(template_parameters: &String) -> Value {
-    let json_result;
-    if template_parameters.len() != 0 {
-        let template_params = &template_parameters;
-        json_result = match param_to_request_body(template_params) {
-            Ok(json_result) => json_result,
-            Err(e) => panic!("failed json parsing: {}", e),
-        };
-        json_result
-    } else {
-        json_result = Value::Object(Map::new());
-        json_result
+/// Resolve which `TokenCredential` to build: an explicit `--auth` value
+/// wins, otherwise auto-detect a service principal from its env vars and
+/// fall back to the PAT.
+fn build_credential(auth: Option<&str>) -> Result<Arc<dyn TokenCredential>, Box<dyn Error>> {
+    let mode = auth.map(str::to_string).unwrap_or_else(detect_auth_mode);
+
+    match mode.as_str() {
+        "service-principal" => Ok(Arc::new(ClientSecretCredential::new(
+            env::var(AZURE_TENANT_ID_ENV)?,
+            env::var(AZURE_CLIENT_ID_ENV)?,
+            env::var(AZURE_CLIENT_SECRET_ENV)?,
+        ))),
+        _ => Ok(Arc::new(PatCredential::new(get_pat_from_env()?))),
     }
 }
 
-// --------------------------------------------------
-
-// Pipeline run URL builder function
-fn pipeline_run_url(config: &Config) -> String {
-    format!(
-        "https://dev.azure.com/{}/{}/_apis/pipelines/{}/runs?api-version=7.1-preview.1",
-        config.organization, config.project, config.pipeline_id
-    )
-}
-
-async fn pipeline_exec(
-    client: &Client,
-    config: &Config,
-    pat: &String,
-) -> Result<reqwest::Response, Box<dyn Error>> {
-    // Send a POST request to trigger a pipeline run
-    let response = client
-        .post(&pipeline_run_url(&config))
-        .header(header::ACCEPT, "application/json")
-        .header(header::CONTENT_TYPE, "application/json")
-        .header(
-            header::AUTHORIZATION,
-            format!("Basic {}", base64::encode(&format!(":{}", pat))),
-        )
-        .json(&param_str_to_json(&config.template_parameters))
-        .send()
-        .await?;
-
-    Ok(response)
-}
-
-async fn pipeline_validate_response(
-    client: &Client,
-    response: reqwest::Response,
-    config: &Config,
-    pat: &String,
-) -> Result<(), Box<dyn Error>> {
-    // Check the response status code
-    let status_code = response.status();
-
-    match status_code {
-        reqwest::StatusCode::OK => {
-            let body = response.bytes().await?;
-            let response_str = String::from_utf8_lossy(&body);
-            let response_object: PipeLineResponse = serde_json::from_str(&response_str).unwrap();
-
-            println!(
-                "Pipeline [{}] with id [{}] triggered successfully, run id = [{}]",
-                response_object.pipeline.name, response_object.pipeline.id, response_object.id
-            );
-
-            if config.watch == true {
-                // Call the watch function asynchronously
-                let watch_result = pipeline_watch(&client, &config, &pat, response_object.id).await;
+fn detect_auth_mode() -> String {
+    let has_service_principal = env::var(AZURE_TENANT_ID_ENV).is_ok()
+        && env::var(AZURE_CLIENT_ID_ENV).is_ok()
+        && env::var(AZURE_CLIENT_SECRET_ENV).is_ok();
 
-                // Handle the result of the watch function
-                match watch_result {
-                    Ok(()) => Ok(()),
-                    Err(err) => {
-                        eprintln!("Error in watch function: {}", err);
-                        std::process::exit(1);
-                    }
-                }
-            } else {
-                Ok(())
-            }
-        }
-        _ => {
-            let api_response: ApiResponse = response.json().await?;
-            let err_msg = format!(
-                "Failed to trigger the pipeline run, status code: {:?} \nMessage: {:?}",
-                status_code, api_response.message,
-            );
-            Err(err_msg.into())
-        }
-    }
-}
-
-async fn pipeline_watch(
-    client: &Client,
-    config: &Config,
-    pat: &String,
-    run_id: u32,
-) -> Result<(), Box<dyn Error>> {
-    let pipeline_status_url = format!(
-        "https://dev.azure.com/{}/{}/_apis/pipelines/{}/runs/{}?api-version=7.1-preview.1",
-        config.organization, config.project, config.pipeline_id, run_id
-    );
-
-    loop {
-        // Send a GET request to the Azure DevOps API to get the pipeline run status
-        let response = client
-            .get(&pipeline_status_url)
-            .header(header::ACCEPT, "application/json")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header(
-                header::AUTHORIZATION,
-                format!("Basic {}", base64::encode(&format!(":{}", pat))),
-            )
-            .send()
-            .await?;
-
-        // Check if the request was successful
-        if response.status().is_success() {
-            let status_json: PipeLineResponse = response.json().await?;
-            let status = status_json.state.as_str();
-            // Check if the pipeline has finished executing
-            if status == "completed" || status == "canceled" || status == "failed" {
-                println!("Pipeline has finished with status: {}", status);
-                break; // Exit the loop
-            } else {
-                println!("Pipeline status: {}", status);
-            }
-        } else {
-            eprintln!(
-                "Failed to retrieve pipeline status: {:?}",
-                response.status()
-            );
-        }
-        // Sleep for a while before checking again (e.g., every 30 seconds)
-        thread::sleep(Duration::from_secs(10));
+    if has_service_principal {
+        "service-principal".to_string()
+    } else {
+        "pat".to_string()
     }
-    Ok(())
 }
-
-// --------------------------------------------------
-// UNIT TESTS
-// --------------------------------------------------
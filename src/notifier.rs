@@ -0,0 +1,94 @@
+//! Completion notifications fired when a watched run reaches a terminal
+//! state, mirroring the CI driver's `notifier` module. Multiple sinks can
+//! be registered on an [`crate::AzpClient`]; each is a [`Notifier`].
+
+use crate::{AzpError, RunId, RunState};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+
+/// Details of a run that just reached a terminal state.
+pub struct RunEvent {
+    pub pipeline: String,
+    pub run_id: RunId,
+    pub state: RunState,
+    pub url: String,
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, event: &RunEvent) -> Result<(), AzpError>;
+}
+
+/// POSTs `{ "pipeline", "run_id", "state", "url" }` to an arbitrary
+/// webhook URL.
+pub struct WebhookNotifier {
+    url: String,
+    http: Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: impl Into<String>) -> Self {
+        WebhookNotifier {
+            url: url.into(),
+            http: Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, event: &RunEvent) -> Result<(), AzpError> {
+        let payload = json!({
+            "pipeline": event.pipeline,
+            "run_id": event.run_id,
+            "state": event.state.to_string(),
+            "url": event.url,
+        });
+
+        let response = self.http.post(&self.url).json(&payload).send().await?;
+        if !response.status().is_success() {
+            return Err(AzpError::Api {
+                status: response.status(),
+                message: "notification webhook rejected the payload".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Posts to a Slack incoming webhook using its `{ "text": "..." }`
+/// message format.
+pub struct SlackNotifier {
+    url: String,
+    http: Client,
+}
+
+impl SlackNotifier {
+    pub fn new(url: impl Into<String>) -> Self {
+        SlackNotifier {
+            url: url.into(),
+            http: Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    async fn notify(&self, event: &RunEvent) -> Result<(), AzpError> {
+        let text = format!(
+            "Pipeline *{}* (run {}) finished with status `{}`: {}",
+            event.pipeline, event.run_id, event.state, event.url
+        );
+        let payload = json!({ "text": text });
+
+        let response = self.http.post(&self.url).json(&payload).send().await?;
+        if !response.status().is_success() {
+            return Err(AzpError::Api {
+                status: response.status(),
+                message: "Slack incoming webhook rejected the payload".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Orchestrate several pipelines with dependencies between them,
+//! inspired by the dependency-graph-of-objects shape a data pipeline
+//! model uses. A `Plan` is a set of `PlanNode`s; nodes whose dependencies
+//! have all completed successfully run concurrently, each blocking
+//! (like `--watch`) until its own run is terminal before its dependents
+//! start.
+
+use crate::{AzpClient, AzpError, Config, RunState};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlanNode {
+    pub id: String,
+    pub pipeline_id: u32,
+    #[serde(default)]
+    pub template_parameters: String,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+/// A DAG of pipeline runs, as parsed from a `--plan <file.json>`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Plan {
+    pub nodes: Vec<PlanNode>,
+}
+
+/// What happened to a single node once the plan finished running.
+#[derive(Debug, Clone)]
+pub enum NodeOutcome {
+    /// The run reached a terminal pipeline state.
+    Finished(RunState),
+    /// A dependency did not finish as `RunState::Completed` (it failed,
+    /// was canceled, errored, or was itself aborted), so this node never
+    /// started.
+    Aborted,
+    /// Triggering or watching the run itself errored (not a pipeline
+    /// failure, e.g. a network or auth problem).
+    Error(String),
+}
+
+/// Reject a plan with unknown `depends_on` ids or a dependency cycle
+/// before anything is executed.
+fn validate(plan: &Plan) -> Result<(), AzpError> {
+    let ids: HashSet<&str> = plan.nodes.iter().map(|n| n.id.as_str()).collect();
+
+    for node in &plan.nodes {
+        for dep in &node.depends_on {
+            if !ids.contains(dep.as_str()) {
+                return Err(AzpError::InvalidPlan(format!(
+                    "node \"{}\" depends on unknown node \"{}\"",
+                    node.id, dep
+                )));
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Visiting,
+        Done,
+    }
+
+    let by_id: HashMap<&str, &PlanNode> = plan.nodes.iter().map(|n| (n.id.as_str(), n)).collect();
+    let mut marks: HashMap<&str, Mark> = HashMap::new();
+
+    fn visit<'a>(
+        id: &'a str,
+        by_id: &HashMap<&'a str, &'a PlanNode>,
+        marks: &mut HashMap<&'a str, Mark>,
+    ) -> Result<(), AzpError> {
+        match marks.get(id) {
+            Some(Mark::Done) => return Ok(()),
+            Some(Mark::Visiting) => {
+                return Err(AzpError::InvalidPlan(format!(
+                    "plan has a dependency cycle involving node \"{}\"",
+                    id
+                )))
+            }
+            None => {}
+        }
+
+        marks.insert(id, Mark::Visiting);
+        for dep in &by_id[id].depends_on {
+            visit(dep, by_id, marks)?;
+        }
+        marks.insert(id, Mark::Done);
+        Ok(())
+    }
+
+    for id in by_id.keys() {
+        visit(id, &by_id, &mut marks)?;
+    }
+
+    Ok(())
+}
+
+/// Run every node in `plan`, respecting `depends_on`. Independent nodes
+/// run concurrently; a node only starts once all of its dependencies
+/// have finished successfully. Returns the outcome of every node, in the
+/// order they were declared.
+pub async fn run_plan(
+    client: Arc<AzpClient>,
+    base_config: &Config,
+    plan: Plan,
+) -> Result<Vec<(String, NodeOutcome)>, AzpError> {
+    validate(&plan)?;
+
+    let node_order: Vec<String> = plan.nodes.iter().map(|n| n.id.clone()).collect();
+    let mut senders: HashMap<String, broadcast::Sender<NodeOutcome>> = HashMap::new();
+
+    for node in &plan.nodes {
+        let (tx, _rx) = broadcast::channel(1);
+        senders.insert(node.id.clone(), tx);
+    }
+
+    // Every dependent must subscribe to its dependencies' channels before
+    // any task is spawned: a node can in principle finish synchronously
+    // (e.g. a mocked client), and a `send` only reaches receivers that
+    // already existed when it happened.
+    let mut dep_receivers_by_node: HashMap<String, Vec<broadcast::Receiver<NodeOutcome>>> =
+        plan.nodes
+            .iter()
+            .map(|node| {
+                let receivers = node
+                    .depends_on
+                    .iter()
+                    .map(|dep| senders[dep].subscribe())
+                    .collect();
+                (node.id.clone(), receivers)
+            })
+            .collect();
+
+    let mut tasks = Vec::new();
+    for node in plan.nodes {
+        let client = Arc::clone(&client);
+        let organization = base_config.organization.clone();
+        let project = base_config.project.clone();
+        let sender = senders[&node.id].clone();
+        let mut dep_receivers = dep_receivers_by_node.remove(&node.id).unwrap();
+
+        tasks.push(tokio::spawn(async move {
+            let mut dependencies_ok = true;
+            for rx in &mut dep_receivers {
+                match rx.recv().await {
+                    Ok(NodeOutcome::Finished(RunState::Completed)) => {}
+                    _ => dependencies_ok = false,
+                }
+            }
+
+            let outcome = if !dependencies_ok {
+                NodeOutcome::Aborted
+            } else {
+                let node_config = Config {
+                    organization,
+                    project,
+                    pipeline_id: node.pipeline_id,
+                    template_parameters: node.template_parameters,
+                    watch: true,
+                };
+
+                match client.run_pipeline(&node_config).await {
+                    Ok(run_id) => match client.watch_run(&node_config, run_id).await {
+                        Ok(state) => NodeOutcome::Finished(state),
+                        Err(err) => NodeOutcome::Error(err.to_string()),
+                    },
+                    Err(err) => NodeOutcome::Error(err.to_string()),
+                }
+            };
+
+            // A receiver-less send just means nothing depends on this
+            // node; that's fine.
+            let _ = sender.send(outcome.clone());
+            (node.id, outcome)
+        }));
+    }
+
+    let mut results: HashMap<String, NodeOutcome> = HashMap::new();
+    for task in tasks {
+        let (id, outcome) = task
+            .await
+            .map_err(|err| AzpError::InvalidPlan(format!("node task panicked: {}", err)))?;
+        results.insert(id, outcome);
+    }
+
+    Ok(node_order
+        .into_iter()
+        .map(|id| {
+            let outcome = results.remove(&id).expect("every node produces a result");
+            (id, outcome)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, depends_on: &[&str]) -> PlanNode {
+        PlanNode {
+            id: id.to_string(),
+            pipeline_id: 1,
+            template_parameters: String::new(),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_an_acyclic_plan() {
+        let plan = Plan {
+            nodes: vec![node("a", &[]), node("b", &["a"]), node("c", &["a", "b"])],
+        };
+        assert!(validate(&plan).is_ok());
+    }
+
+    #[test]
+    fn rejects_an_unknown_dependency() {
+        let plan = Plan {
+            nodes: vec![node("a", &["missing"])],
+        };
+        let err = validate(&plan).unwrap_err();
+        assert!(matches!(err, AzpError::InvalidPlan(_)));
+    }
+
+    #[test]
+    fn rejects_a_direct_cycle() {
+        let plan = Plan {
+            nodes: vec![node("a", &["b"]), node("b", &["a"])],
+        };
+        let err = validate(&plan).unwrap_err();
+        assert!(matches!(err, AzpError::InvalidPlan(_)));
+    }
+
+    #[test]
+    fn rejects_a_longer_cycle() {
+        let plan = Plan {
+            nodes: vec![node("a", &["b"]), node("b", &["c"]), node("c", &["a"])],
+        };
+        let err = validate(&plan).unwrap_err();
+        assert!(matches!(err, AzpError::InvalidPlan(_)));
+    }
+}